@@ -1,19 +1,80 @@
 use clap::Parser;
+use std::net::ToSocketAddrs;
+
 mod q3_client;
+mod q3_error;
+mod q3_query;
+mod string_utils;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    #[arg(short = 's', long)]
-    host: String,
+    /// Server to query, host:port. May be repeated to query several
+    /// servers concurrently.
+    #[arg(short = 's', long, required = true)]
+    host: Vec<String>,
+
+    /// Send a lightweight getinfo request instead of getstatus (no player
+    /// list). Only valid when a single host is given.
+    #[arg(long)]
+    info: bool,
+
+    /// Print the player list with ANSI color codes instead of dumping
+    /// getstatus as JSON. Only valid when a single host is given.
+    #[arg(long)]
+    color: bool,
 }
 
 fn main() {
     let args = Args::parse();
 
-    let client = q3_client::Q3Client::new(args.host);
+    if args.host.len() > 1 && (args.info || args.color) {
+        eprintln!("error: --info and --color are only valid when a single --host is given");
+        std::process::exit(1);
+    }
+
+    if args.host.len() == 1 {
+        let client = q3_client::Q3Client::new(args.host[0].clone());
+
+        if args.info {
+            match client.get_info() {
+                Ok(info) => println!("{}", serde_json::to_string_pretty(&info).unwrap()),
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+
+        match client.get_status() {
+            Ok(status) if args.color => {
+                for player in &status.players {
+                    println!("{}", string_utils::to_ansi(&player.segments));
+                }
+            }
+            Ok(status) => println!("{}", serde_json::to_string_pretty(&status).unwrap()),
+            Err(err) => {
+                eprintln!("error: {}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let addresses = args
+        .host
+        .iter()
+        .filter_map(|host| match host.to_socket_addrs() {
+            Ok(mut addrs) => addrs.next(),
+            Err(err) => {
+                eprintln!("error: could not resolve {}: {}", host, err);
+                None
+            }
+        })
+        .collect::<Vec<_>>();
 
-    let status = client.get_status().unwrap();
+    let results = q3_query::Q3Query::new().get_statuses(&addresses);
 
-    println!("{}", serde_json::to_string_pretty(&status).unwrap());
+    println!("{}", serde_json::to_string_pretty(&results).unwrap());
 }
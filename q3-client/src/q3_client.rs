@@ -1,14 +1,24 @@
 use serde_derive::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::error::Error;
-use std::net::UdpSocket;
-use std::time::Duration;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::q3_error::Q3Error;
+use crate::string_utils::{self, ColorSegment};
+
+/// Largest payload a UDP datagram can carry over IPv4/IPv6.
+const MAX_UDP_PAYLOAD_SIZE: usize = 65_527;
+
+/// Upper bound on how long to wait for a continuation datagram after the
+/// first one arrives, before assuming the response is complete.
+const CONTINUATION_GRACE_PERIOD_CAP: Duration = Duration::from_millis(100);
 
 pub struct Q3Client {
     hostname: String,
     options: Q3ClientOptions,
 }
 
+#[derive(Clone)]
 pub struct Q3ClientOptions {
     pub read_timeout: Duration,
     pub write_timeout: Duration,
@@ -18,12 +28,26 @@ pub struct Q3ClientOptions {
 pub struct Player {
     pub name: String,
     pub clean_name: String,
+    /// Color-coded segments of `name`, for consumers that want to render
+    /// it with its original colors instead of `clean_name`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub segments: Vec<ColorSegment>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ServerStatus {
     pub keys: HashMap<String, String>,
     pub players: Vec<Player>,
+    /// Round-trip time of the getstatus request, in milliseconds. Always
+    /// set by `get_status`; a query that times out returns
+    /// `Q3Error::Timeout` instead of a status. Omitted from JSON when `None`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ping: Option<f32>,
+    /// The address that actually answered, i.e. the one `hostname`
+    /// resolved to and was queried on. Useful when a name resolves to
+    /// several addresses (IPv4, IPv6, or multiple hosts behind round-robin
+    /// DNS).
+    pub resolved_address: SocketAddr,
 }
 
 impl Q3Client {
@@ -41,140 +65,274 @@ impl Q3Client {
         Self { hostname, options }
     }
 
-    pub fn get_status(self: &Self) -> Result<ServerStatus, Box<dyn Error>> {
-        let mut status = ServerStatus {
-            keys: HashMap::new(),
-            players: Vec::new(),
-        };
+    pub fn get_status(self: &Self) -> Result<ServerStatus, Q3Error> {
+        // A full server's statusResponse can be split across several
+        // datagrams, so keep listening for continuations.
+        let (response, ping, resolved_address) = self.send_and_receive(b"getstatus", true)?;
 
-        let socket = UdpSocket::bind("0.0.0.0:0");
-        if let Err(e) = socket {
-            return Err(e.into());
-        }
+        let mut status = Q3Client::parse_status_response(&response, resolved_address)?;
+        status.ping = Some(ping);
+
+        Ok(status)
+    }
+
+    /// Sends a `getinfo` request, which is much cheaper than `getstatus`
+    /// since it skips the player list, and is useful for quickly scanning
+    /// many servers. The reply's `challenge` key is checked against the
+    /// one we sent to reject spoofed or stale packets.
+    pub fn get_info(self: &Self) -> Result<HashMap<String, String>, Q3Error> {
+        let challenge = Q3Client::generate_challenge();
+        let command = format!("getinfo {}", challenge);
+        // infoResponse always fits in a single datagram, so there's no
+        // continuation to wait for.
+        let (response, _ping, _address) = self.send_and_receive(command.as_bytes(), false)?;
+
+        Q3Client::parse_info_response(&response, &challenge)
+    }
+
+    fn generate_challenge() -> String {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|duration| duration.subsec_nanos())
+            .unwrap_or(0);
+
+        format!("{:x}", nanos)
+    }
+
+    fn parse_info_response(response: &str, challenge: &str) -> Result<HashMap<String, String>, Q3Error> {
+        let rows = response.split("\n").collect::<Vec<&str>>();
 
-        let socket = socket.unwrap();
-        let timeout_result = socket.set_read_timeout(Some(self.options.read_timeout));
-        if let Err(e) = timeout_result {
-            return Err(e.into());
+        if rows.is_empty() || !rows[0].ends_with("infoResponse") {
+            return Err(Q3Error::InvalidResponse {
+                message: "missing or malformed infoResponse header".to_string(),
+                raw: response.to_string(),
+            });
         }
-        let timeout_result = socket.set_write_timeout(Some(self.options.write_timeout));
-        if let Err(e) = timeout_result {
-            return Err(e.into());
+
+        let info = rows
+            .get(1)
+            .map(|line| Q3Client::parse_keys(line))
+            .unwrap_or_default();
+
+        match info.get("challenge") {
+            Some(echoed) if echoed == challenge => Ok(info),
+            Some(_) => Err(Q3Error::Protocol(
+                "getinfo response echoed a different challenge than we sent".to_string(),
+            )),
+            None => Err(Q3Error::InvalidResponse {
+                message: "getinfo response missing challenge key".to_string(),
+                raw: response.to_string(),
+            }),
         }
+    }
+
+    fn resolve_address(self: &Self) -> Result<SocketAddr, Q3Error> {
+        self.hostname
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| Q3Error::Protocol(format!("could not resolve {}", self.hostname)))
+    }
+
+    fn send_and_receive(
+        self: &Self,
+        command: &[u8],
+        expect_continuation: bool,
+    ) -> Result<(String, f32, SocketAddr), Q3Error> {
+        let address = self.resolve_address()?;
+
+        let bind_address = match address {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+
+        let socket = UdpSocket::bind(bind_address)?;
+        socket.set_read_timeout(Some(self.options.read_timeout))?;
+        socket.set_write_timeout(Some(self.options.write_timeout))?;
 
-        // join 0xff, 0xff, 0xff, 0xff and getstatus (as string)
+        // join 0xff, 0xff, 0xff, 0xff and the command (as bytes)
         let prefix: [u8; 4] = [0xff, 0xff, 0xff, 0xff];
-        let getstatus = String::from("getstatus");
-        let buf = [&prefix, getstatus.as_bytes()].concat();
+        let buf = [&prefix, command].concat();
 
-        let send_result = socket.send_to(&buf, &self.hostname);
-        if let Err(e) = send_result {
-            return Err(e.into());
-        }
+        let sent_at = Instant::now();
+        socket.send_to(&buf, address)?;
 
-        let mut buf: [u8; 1024] = [0; 1024];
-        let receive_result = socket.recv_from(&mut buf);
-        if let Err(e) = receive_result {
-            return Err(e.into());
+        let mut packet = vec![0u8; MAX_UDP_PAYLOAD_SIZE];
+        let bytes_read = match socket.recv_from(&mut packet) {
+            Ok((bytes_read, _)) => bytes_read,
+            Err(e) if Q3Client::is_timeout(&e) => return Err(Q3Error::Timeout),
+            Err(e) => return Err(e.into()),
+        };
+        let ping = sent_at.elapsed().as_secs_f32() * 1000.0;
+
+        let mut raw = packet[..bytes_read].to_vec();
+
+        // getstatus replies can be split across several datagrams; keep
+        // listening for continuations, giving up as soon as none arrive
+        // within a short grace window. getinfo always fits in one
+        // datagram, so there's nothing to wait for.
+        if expect_continuation {
+            socket.set_read_timeout(Some(self.continuation_grace_period()))?;
+            loop {
+                match socket.recv_from(&mut packet) {
+                    Ok((bytes_read, _)) => raw.extend_from_slice(&packet[..bytes_read]),
+                    Err(e) if Q3Client::is_timeout(&e) => break,
+                    Err(e) => return Err(e.into()),
+                }
+            }
         }
 
-        let (bytes_read, _) = receive_result.unwrap();
-        let response = String::from_utf8_lossy(&buf[..bytes_read]);
+        // Decode only once every continuation has been collected, so a
+        // split that lands mid-character can't corrupt the result.
+        Ok((String::from_utf8_lossy(&raw).into_owned(), ping, address))
+    }
+
+    /// How long to wait for a continuation datagram, scaled to the
+    /// caller's configured `read_timeout` so a batch scan with a short
+    /// timeout doesn't pay a fixed latency tax on every response.
+    fn continuation_grace_period(self: &Self) -> Duration {
+        (self.options.read_timeout / 20).min(CONTINUATION_GRACE_PERIOD_CAP)
+    }
+
+    fn is_timeout(e: &std::io::Error) -> bool {
+        matches!(
+            e.kind(),
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+        )
+    }
 
+    fn parse_status_response(
+        response: &str,
+        resolved_address: SocketAddr,
+    ) -> Result<ServerStatus, Q3Error> {
         let rows = response.split("\n").collect::<Vec<&str>>();
 
-        let keys = rows[1].split("\\").skip(1).collect::<Vec<&str>>();
-        let mut current_key = keys[1].to_string();
-        for val in &keys[1..] {
-            if current_key == "" {
-                current_key = val.to_string();
-                continue;
-            }
+        if rows.len() < 2 || !rows[0].ends_with("statusResponse") {
+            return Err(Q3Error::InvalidResponse {
+                message: "missing or malformed statusResponse header".to_string(),
+                raw: response.to_string(),
+            });
+        }
+
+        let mut status = ServerStatus {
+            keys: HashMap::new(),
+            players: Vec::new(),
+            ping: None,
+            resolved_address,
+        };
+
+        status.keys = Q3Client::parse_keys(rows[1]);
 
-            status.keys.insert(current_key, val.to_string());
-            current_key = String::from("");
+        if rows.len() > 2 {
+            status.players = rows[2..rows.len() - 1]
+                .iter()
+                .filter(|row| !row.is_empty())
+                .map(|row| Q3Client::parse_player(row))
+                .collect::<Result<Vec<Player>, Q3Error>>()?;
         }
 
-        let players = rows[2..rows.len() - 1]
-            .iter()
-            .map(|row| Q3Client::parse_player_name(row))
-            .collect::<Vec<String>>();
+        Ok(status)
+    }
 
-        status.players = players
-            .iter()
-            .map(|player| Player {
-                name: player.to_string(),
-                clean_name: string_utils::sanitize_string(player),
-            })
-            .collect::<Vec<Player>>();
+    /// Parses a `\key\value\key\value...` line, as used by both the
+    /// `statusResponse` and `infoResponse` datagrams.
+    fn parse_keys(line: &str) -> HashMap<String, String> {
+        let mut keys = HashMap::new();
+
+        let fields = line.split("\\").skip(1).collect::<Vec<&str>>();
+        let mut current_key: Option<String> = None;
+        for field in fields {
+            match current_key.take() {
+                None => current_key = Some(field.to_string()),
+                Some(key) => {
+                    keys.insert(key, field.to_string());
+                }
+            }
+        }
 
-        return Ok(status);
+        keys
     }
 
-    fn parse_player_name(get_status_player: &str) -> String {
-        return get_status_player.split("\"").collect::<Vec<&str>>()[1].to_string();
+    fn parse_player(get_status_player: &str) -> Result<Player, Q3Error> {
+        let name = get_status_player
+            .split("\"")
+            .nth(1)
+            .ok_or_else(|| Q3Error::InvalidResponse {
+                message: "player row missing quoted name".to_string(),
+                raw: get_status_player.to_string(),
+            })?;
+
+        Ok(Player {
+            name: name.to_string(),
+            clean_name: string_utils::sanitize_string(name),
+            segments: string_utils::parse_segments(name),
+        })
     }
 }
 
-mod string_utils {
-    // sanitize_string removes all color codes from a original string
-    pub fn sanitize_string(orig_string: &str) -> String {
-        let mut cleaned_string = String::from("");
-
-        let mut i = 0;
-        while i < orig_string.len() {
-            let c = orig_string.chars().nth(i).unwrap();
-            if c == '^' {
-                if orig_string.chars().nth(i + 1) == Some('^') {
-                    cleaned_string += "^";
-                    i += 1;
-                    continue;
-                }
-                i += 2;
-                continue;
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            cleaned_string += &c.to_string();
-            i += 1;
-        }
+    fn test_address() -> SocketAddr {
+        "127.0.0.1:27960".parse().unwrap()
+    }
 
-        return cleaned_string;
+    #[test]
+    fn test_parse_status_response_rejects_malformed_header() {
+        let response = "\u{FFFD}\u{FFFD}\u{FFFD}\u{FFFD}notStatusResponse\n\\sv_hostname\\My Server\n";
+
+        let err = Q3Client::parse_status_response(response, test_address()).unwrap_err();
+
+        assert!(matches!(err, Q3Error::InvalidResponse { .. }));
     }
 
-    #[cfg(test)]
-    mod tests {
-        use super::*;
+    #[test]
+    fn test_parse_status_response_rejects_short_input() {
+        let response = "\u{FFFD}\u{FFFD}\u{FFFD}\u{FFFD}statusResponse";
 
-        #[test]
-        fn test_sanitize_string() {
-            let player_name = "^1Player^7Name";
-            let expected = "PlayerName";
+        let err = Q3Client::parse_status_response(response, test_address()).unwrap_err();
 
-            assert_eq!(sanitize_string(player_name), expected);
-        }
+        assert!(matches!(err, Q3Error::InvalidResponse { .. }));
+    }
 
-        #[test]
-        fn test_sanitize_string_with_double_caret() {
-            let player_name = "^1Player^^7Name";
-            let expected = "Player^Name";
+    #[test]
+    fn test_parse_status_response_parses_keys_and_players() {
+        let response = "\u{FFFD}\u{FFFD}\u{FFFD}\u{FFFD}statusResponse\n\\sv_hostname\\My Server\\g_gametype\\0\n0 0 \"Player1\"\n0 0 \"Player2\"\n";
 
-            assert_eq!(sanitize_string(player_name), expected);
-        }
+        let status = Q3Client::parse_status_response(response, test_address()).unwrap();
 
-        #[test]
-        fn test_sanitize_string_with_no_caret() {
-            let player_name = "PlayerName";
-            let expected = "PlayerName";
+        assert_eq!(status.keys.get("sv_hostname"), Some(&"My Server".to_string()));
+        assert_eq!(status.resolved_address, test_address());
+        assert_eq!(status.players.len(), 2);
+        assert_eq!(status.players[0].clean_name, "Player1");
+        assert_eq!(status.players[1].clean_name, "Player2");
+    }
 
-            assert_eq!(sanitize_string(player_name), expected);
-        }
+    #[test]
+    fn test_parse_info_response_accepts_matching_challenge() {
+        let response = "\u{FFFD}\u{FFFD}\u{FFFD}\u{FFFD}infoResponse\n\\sv_hostname\\My Server\\challenge\\abc123\n";
 
-        #[test]
-        fn test_sanitize_string_with_triple_caret() {
-            let player_name = "^1Player^^^7Name";
-            let expected = "Player^^Name";
+        let info = Q3Client::parse_info_response(response, "abc123").unwrap();
 
-            assert_eq!(sanitize_string(player_name), expected);
-        }
+        assert_eq!(info.get("sv_hostname"), Some(&"My Server".to_string()));
+        assert_eq!(info.get("challenge"), Some(&"abc123".to_string()));
+    }
+
+    #[test]
+    fn test_parse_info_response_rejects_mismatched_challenge() {
+        let response = "\u{FFFD}\u{FFFD}\u{FFFD}\u{FFFD}infoResponse\n\\sv_hostname\\My Server\\challenge\\spoofed\n";
+
+        let err = Q3Client::parse_info_response(response, "abc123").unwrap_err();
+
+        assert!(matches!(err, Q3Error::Protocol(_)));
+    }
+
+    #[test]
+    fn test_parse_info_response_rejects_missing_challenge() {
+        let response = "\u{FFFD}\u{FFFD}\u{FFFD}\u{FFFD}infoResponse\n\\sv_hostname\\My Server\n";
+
+        let err = Q3Client::parse_info_response(response, "abc123").unwrap_err();
+
+        assert!(matches!(err, Q3Error::InvalidResponse { .. }));
     }
 }
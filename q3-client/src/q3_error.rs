@@ -0,0 +1,48 @@
+use serde_derive::Serialize;
+use thiserror::Error;
+
+use crate::q3_client::ServerStatus;
+
+/// Errors that can occur while querying a Quake 3 server.
+#[derive(Debug, Error)]
+pub enum Q3Error {
+    #[error("i/o error communicating with server: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("server did not respond within the configured timeout")]
+    Timeout,
+
+    #[error("protocol error: {0}")]
+    Protocol(String),
+
+    #[error("invalid response: {message}")]
+    InvalidResponse { message: String, raw: String },
+}
+
+/// The outcome of querying a single server, distinguishing a healthy
+/// response from the different ways a query can fail. Used by the
+/// batch/master-server query path so that one bad server doesn't abort
+/// the whole run.
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ServerResultKind {
+    Ok { status: ServerStatus },
+    Timeout,
+    Invalid { message: String, response: String },
+    Protocol { message: String },
+    Io { message: String },
+}
+
+impl From<Result<ServerStatus, Q3Error>> for ServerResultKind {
+    fn from(result: Result<ServerStatus, Q3Error>) -> Self {
+        match result {
+            Ok(status) => ServerResultKind::Ok { status },
+            Err(Q3Error::Timeout) => ServerResultKind::Timeout,
+            Err(Q3Error::InvalidResponse { message, raw }) => {
+                ServerResultKind::Invalid { message, response: raw }
+            }
+            Err(Q3Error::Protocol(message)) => ServerResultKind::Protocol { message },
+            Err(Q3Error::Io(e)) => ServerResultKind::Io { message: e.to_string() },
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::thread;
+
+use crate::q3_client::{Q3Client, Q3ClientOptions};
+use crate::q3_error::{Q3Error, ServerResultKind};
+
+/// Header that prefixes a master server's `getserversResponse` packet,
+/// after the `\xff\xff\xff\xff` out-of-band marker.
+const GETSERVERS_RESPONSE_HEADER: &[u8] = b"getserversResponse";
+
+/// Marks the end of the address list in a `getserversResponse` packet.
+const GETSERVERS_EOT_MARKER: &[u8] = b"EOT\0\0\0";
+
+/// Queries a batch of Quake 3 servers (e.g. the address list returned by
+/// [`Q3Query::fetch_master_servers`]) concurrently, so a single
+/// unreachable server doesn't hold up the rest of the list.
+pub struct Q3Query {
+    options: Q3ClientOptions,
+}
+
+impl Q3Query {
+    pub fn new() -> Self {
+        Q3Query::new_with_options(Q3ClientOptions {
+            read_timeout: std::time::Duration::from_secs(5),
+            write_timeout: std::time::Duration::from_secs(5),
+        })
+    }
+
+    pub fn new_with_options(options: Q3ClientOptions) -> Self {
+        Self { options }
+    }
+
+    /// Sends a `getservers` request to a master server and parses the
+    /// `getserversResponse` packet into the list of game server addresses
+    /// it advertises, so the result can be fed straight into
+    /// [`Q3Query::get_statuses`].
+    ///
+    /// Only the IPv4 `getservers`/`getserversResponse` pair is supported;
+    /// the IPv6 `getserversExt`/`getserversExtResponse` variant is not
+    /// handled.
+    pub fn fetch_master_servers(
+        self: &Self,
+        master_hostname: &str,
+        game: &str,
+        protocol: &str,
+    ) -> Result<Vec<SocketAddr>, Q3Error> {
+        let address = master_hostname
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| Q3Error::Protocol(format!("could not resolve {}", master_hostname)))?;
+
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.set_read_timeout(Some(self.options.read_timeout))?;
+        socket.set_write_timeout(Some(self.options.write_timeout))?;
+
+        let command = format!("getservers {} {} empty full", game, protocol);
+        let prefix: [u8; 4] = [0xff, 0xff, 0xff, 0xff];
+        let buf = [&prefix, command.as_bytes()].concat();
+        socket.send_to(&buf, address)?;
+
+        let mut raw = Vec::new();
+        let mut packet = [0u8; 4096];
+        loop {
+            match socket.recv_from(&mut packet) {
+                Ok((bytes_read, _)) => {
+                    raw.extend_from_slice(&packet[..bytes_read]);
+                    if raw.ends_with(GETSERVERS_EOT_MARKER) {
+                        break;
+                    }
+                }
+                Err(e) if Q3Query::is_timeout(&e) => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(Q3Query::parse_getservers_response(&raw))
+    }
+
+    /// Parses the packed IP:port entries out of a `getserversResponse`
+    /// packet. Each entry is a `\` byte followed by 4 bytes of IPv4
+    /// address and 2 bytes of big-endian port; the list is terminated by
+    /// a `\EOT\0\0\0` entry.
+    fn parse_getservers_response(raw: &[u8]) -> Vec<SocketAddr> {
+        let start = match raw
+            .windows(GETSERVERS_RESPONSE_HEADER.len())
+            .position(|window| window == GETSERVERS_RESPONSE_HEADER)
+        {
+            Some(pos) => pos + GETSERVERS_RESPONSE_HEADER.len(),
+            None => return Vec::new(),
+        };
+
+        let mut addresses = Vec::new();
+        let mut i = start;
+        while i + 7 <= raw.len() {
+            if raw[i] != b'\\' {
+                i += 1;
+                continue;
+            }
+
+            let entry = &raw[i + 1..i + 7];
+            if entry == GETSERVERS_EOT_MARKER {
+                break;
+            }
+
+            let ip = Ipv4Addr::new(entry[0], entry[1], entry[2], entry[3]);
+            let port = u16::from_be_bytes([entry[4], entry[5]]);
+            addresses.push(SocketAddr::from((ip, port)));
+            i += 7;
+        }
+
+        addresses
+    }
+
+    fn is_timeout(e: &std::io::Error) -> bool {
+        matches!(
+            e.kind(),
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+        )
+    }
+
+    /// Queries every address in `addresses` on its own socket in parallel
+    /// and returns the outcome of each, keyed by the address it was sent
+    /// to.
+    pub fn get_statuses(self: &Self, addresses: &[SocketAddr]) -> HashMap<SocketAddr, ServerResultKind> {
+        let options = self.options.clone();
+
+        let handles = addresses
+            .iter()
+            .map(|address| {
+                let address = *address;
+                let options = options.clone();
+                let handle = thread::spawn(move || {
+                    let client = Q3Client::new_with_options(address.to_string(), options);
+                    ServerResultKind::from(client.get_status())
+                });
+                (address, handle)
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .map(|(address, handle)| {
+                let result = handle.join().unwrap_or_else(|_| ServerResultKind::Protocol {
+                    message: "query thread panicked".to_string(),
+                });
+                (address, result)
+            })
+            .collect()
+    }
+}
+
+impl Default for Q3Query {
+    fn default() -> Self {
+        Q3Query::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_getservers_response_extracts_addresses() {
+        let mut raw = Vec::new();
+        raw.extend_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+        raw.extend_from_slice(GETSERVERS_RESPONSE_HEADER);
+        raw.push(b'\\');
+        raw.extend_from_slice(&[127, 0, 0, 1, 0x6f, 0xf8]);
+        raw.push(b'\\');
+        raw.extend_from_slice(&[10, 0, 0, 2, 0x1f, 0x90]);
+        raw.push(b'\\');
+        raw.extend_from_slice(GETSERVERS_EOT_MARKER);
+
+        let addresses = Q3Query::parse_getservers_response(&raw);
+
+        assert_eq!(
+            addresses,
+            vec![
+                SocketAddr::from((Ipv4Addr::new(127, 0, 0, 1), 28664)),
+                SocketAddr::from((Ipv4Addr::new(10, 0, 0, 2), 8080)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_getservers_response_missing_header_returns_empty() {
+        let raw = b"\xff\xff\xff\xffnotTheRightHeader".to_vec();
+
+        assert!(Q3Query::parse_getservers_response(&raw).is_empty());
+    }
+
+    #[test]
+    fn test_get_statuses_times_out_unresponsive_servers() {
+        let listener_a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let listener_b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address_a = listener_a.local_addr().unwrap();
+        let address_b = listener_b.local_addr().unwrap();
+
+        let query = Q3Query::new_with_options(Q3ClientOptions {
+            read_timeout: std::time::Duration::from_millis(50),
+            write_timeout: std::time::Duration::from_millis(50),
+        });
+
+        let results = query.get_statuses(&[address_a, address_b]);
+
+        assert_eq!(results.len(), 2);
+        assert!(matches!(results.get(&address_a), Some(ServerResultKind::Timeout)));
+        assert!(matches!(results.get(&address_b), Some(ServerResultKind::Timeout)));
+    }
+}
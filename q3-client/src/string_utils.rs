@@ -0,0 +1,187 @@
+use serde_derive::{Deserialize, Serialize};
+
+/// A Quake 3 color code, `^0` through `^7`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Q3Color {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Cyan,
+    Magenta,
+    White,
+}
+
+impl Q3Color {
+    fn from_code(code: char) -> Option<Self> {
+        match code {
+            '0' => Some(Q3Color::Black),
+            '1' => Some(Q3Color::Red),
+            '2' => Some(Q3Color::Green),
+            '3' => Some(Q3Color::Yellow),
+            '4' => Some(Q3Color::Blue),
+            '5' => Some(Q3Color::Cyan),
+            '6' => Some(Q3Color::Magenta),
+            '7' => Some(Q3Color::White),
+            _ => None,
+        }
+    }
+
+    fn ansi_code(self) -> &'static str {
+        match self {
+            Q3Color::Black => "30",
+            Q3Color::Red => "31",
+            Q3Color::Green => "32",
+            Q3Color::Yellow => "33",
+            Q3Color::Blue => "34",
+            Q3Color::Cyan => "36",
+            Q3Color::Magenta => "35",
+            Q3Color::White => "37",
+        }
+    }
+}
+
+/// A run of text that shares a single color, as produced by splitting a
+/// string on its `^`-prefixed color codes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ColorSegment {
+    pub text: String,
+    pub color: Q3Color,
+}
+
+/// Splits `orig_string` into color segments, mapping `^0`-`^7` to their
+/// canonical palette. Text before the first color code defaults to
+/// `Q3Color::White`, matching the client's default draw color.
+///
+/// `^^` escapes to a literal caret, but only the first `^` of the pair is
+/// consumed: the second is re-examined on the next pass, so `^^^7` is two
+/// literal carets followed by a `^7` color code.
+/// This mirrors the original sanitizer's behavior.
+pub fn parse_segments(orig_string: &str) -> Vec<ColorSegment> {
+    let chars = orig_string.chars().collect::<Vec<char>>();
+
+    let mut segments = Vec::new();
+    let mut current_color = Q3Color::White;
+    let mut current_text = String::new();
+
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c != '^' {
+            current_text.push(c);
+            i += 1;
+            continue;
+        }
+
+        if chars.get(i + 1) == Some(&'^') {
+            current_text.push('^');
+            i += 1;
+            continue;
+        }
+
+        if !current_text.is_empty() {
+            segments.push(ColorSegment {
+                text: std::mem::take(&mut current_text),
+                color: current_color,
+            });
+        }
+        if let Some(color) = chars.get(i + 1).and_then(|code| Q3Color::from_code(*code)) {
+            current_color = color;
+        }
+        i += 2;
+    }
+
+    if !current_text.is_empty() {
+        segments.push(ColorSegment {
+            text: current_text,
+            color: current_color,
+        });
+    }
+
+    segments
+}
+
+/// Renders segments back to plain text, discarding color information.
+pub fn to_plain(segments: &[ColorSegment]) -> String {
+    segments.iter().map(|segment| segment.text.as_str()).collect()
+}
+
+/// Renders segments as a string with ANSI color escape sequences, suitable
+/// for printing to a terminal.
+pub fn to_ansi(segments: &[ColorSegment]) -> String {
+    let mut rendered = String::new();
+
+    for segment in segments {
+        rendered.push_str("\x1b[");
+        rendered.push_str(segment.color.ansi_code());
+        rendered.push('m');
+        rendered.push_str(&segment.text);
+    }
+
+    if !segments.is_empty() {
+        rendered.push_str("\x1b[0m");
+    }
+
+    rendered
+}
+
+// sanitize_string removes all color codes from a original string
+pub fn sanitize_string(orig_string: &str) -> String {
+    to_plain(&parse_segments(orig_string))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_string() {
+        let player_name = "^1Player^7Name";
+        let expected = "PlayerName";
+
+        assert_eq!(sanitize_string(player_name), expected);
+    }
+
+    #[test]
+    fn test_sanitize_string_with_double_caret() {
+        let player_name = "^1Player^^7Name";
+        let expected = "Player^Name";
+
+        assert_eq!(sanitize_string(player_name), expected);
+    }
+
+    #[test]
+    fn test_sanitize_string_with_no_caret() {
+        let player_name = "PlayerName";
+        let expected = "PlayerName";
+
+        assert_eq!(sanitize_string(player_name), expected);
+    }
+
+    #[test]
+    fn test_sanitize_string_with_triple_caret() {
+        let player_name = "^1Player^^^7Name";
+        let expected = "Player^^Name";
+
+        assert_eq!(sanitize_string(player_name), expected);
+    }
+
+    #[test]
+    fn test_parse_segments() {
+        let segments = parse_segments("^1Red^2Green");
+
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].text, "Red");
+        assert_eq!(segments[0].color, Q3Color::Red);
+        assert_eq!(segments[1].text, "Green");
+        assert_eq!(segments[1].color, Q3Color::Green);
+    }
+
+    #[test]
+    fn test_to_ansi() {
+        let segments = parse_segments("^1Red");
+
+        assert_eq!(to_ansi(&segments), "\x1b[31mRed\x1b[0m");
+    }
+}